@@ -3,156 +3,545 @@ use crate::parquet::error::{fmt_layout_err, ParquetError, ParquetResult};
 use crate::parquet_write::file::WriteOptions;
 use crate::parquet_write::util;
 use crate::parquet_write::util::{build_plain_page, encode_bool_iter, ExactSizedIter};
-use parquet2::encoding::hybrid_rle::encode_u32;
+use ahash::AHashSet;
+use parquet2::encoding::hybrid_rle::{encode_u32, RleEncoder};
 use parquet2::encoding::Encoding;
+use parquet2::format::SizeStatistics;
 use parquet2::page::{DictPage, Page};
 use parquet2::schema::types::PrimitiveType;
 use parquet2::write::DynIter;
 use std::char::DecodeUtf16Error;
-use std::cmp::max;
-use std::collections::HashSet;
+use std::collections::HashMap;
+use xxhash_rust::xxh64::xxh64;
 
-/// Encode the QuestDB symbols to Parquet.
-///
-/// The resulting tuple consists of:
-///   * The parquet dictionary buffer, which is a sequence of the 4-byte-len-prefixed utf8 strings.
-///   * The local keys, which are the indexes into the dictionary buffer.
-///   * The largest key value used, or 0 if no keys were used.
-///
-/// The first argument returned (parquet dict buffer) is encoded in a specific way to be compatible
-/// with QuestDB with zero-read overhead during queries. See `encode_symbols_dict` for details.
-fn encode_symbols_dict(
-    column_vals: &[i32], // The QuestDB symbol column indices (i.e. numeric values).
-    offsets: &[u64],     // Memory-mapped offsets into the QuestDB global symbol table.
-    chars: &[u8], // Memory-mapped global symbol table. Sequence of 4-code-unit-len-prefixed utf16 strings.
-    stats: &mut BinaryMaxMin,
-) -> ParquetResult<(Vec<u8>, Vec<u32>, u32)> {
-    let mut local_keys = Vec::with_capacity(column_vals.len());
-    let mut max_key = 0;
-    for &v in column_vals {
-        if v >= 0 {
-            local_keys.push(v as u32);
-            max_key = max_key.max(v as u32);
-        }
+/// The dictionary layout chosen by `SymbolColumnEncoder::encode_symbols_dict` for a row group's
+/// symbol column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolDictEncoding {
+    /// The original layout: the dict is padded with empty strings so that dictionary index N
+    /// always equals local key N. No lookups needed when querying, at the cost of padding out
+    /// unused keys. See `SymbolColumnEncoder::encode_dict_buffer` for details.
+    KeyAligned,
+    /// A compact layout holding only the symbols actually referenced by the row group, remapped
+    /// to contiguous indices `0..dense_count`. Used when the global symbol table is sparse
+    /// relative to what the row group touches, to avoid inflating the dictionary with
+    /// empty-string padding. See `SymbolColumnEncoder::encode_dict_buffer_compact` for details.
+    Compact,
+}
+
+/// Once unused ("sparse") keys would make up more than this fraction of the key-aligned
+/// dictionary, `SymbolColumnEncoder::encode_symbols_dict` switches to the `Compact` layout
+/// instead.
+const COMPACT_DICT_SPARSE_THRESHOLD: f64 = 0.75;
+
+/// The eight fixed odd salt multipliers used by the Parquet Split-Block Bloom Filter (SBBF)
+/// format to turn the low 32 bits of a value's hash into one set bit per 32-bit word of a block.
+const BLOOM_FILTER_SALT: [u32; 8] = [
+    0x47b6137b, 0x44974d91, 0x8824ad5b, 0xa2b7289d, 0x705495c7, 0x2df1424b, 0x9efc4947, 0x5c6bfb31,
+];
+
+/// Bits per distinct value used to size an SBBF for roughly a 1% false-positive rate.
+const BLOOM_FILTER_BITS_PER_VALUE: usize = 10;
+
+/// Sets one bit in each of `block`'s eight 32-bit words, derived from the low 32 bits (`hash`) of
+/// a value's 64-bit hash via the fixed `BLOOM_FILTER_SALT` multipliers, per the Parquet SBBF spec.
+fn bloom_filter_set_block_bits(block: &mut [u32; 8], hash: u32) {
+    for (word, salt) in block.iter_mut().zip(BLOOM_FILTER_SALT) {
+        let bit = hash.wrapping_mul(salt) >> 27;
+        *word |= 1 << bit;
     }
+}
 
-    let dict_buffer = encode_dict_buffer(&local_keys, offsets, &chars, stats)?;
-    Ok((dict_buffer, local_keys, max_key))
+/// Number of 256-bit blocks needed so an SBBF holding `distinct_count` distinct values hits
+/// roughly a 1% false-positive rate.
+fn bloom_filter_num_blocks(distinct_count: usize) -> usize {
+    let num_bits = (distinct_count.max(1) * BLOOM_FILTER_BITS_PER_VALUE).max(256);
+    num_bits.div_ceil(256)
 }
 
-/// Encode the parquet dict buffer from the QuestDB symbols + usages.
-///
-/// The aim is to preserve the same numeric values in the column as the original QuestDB column.
-/// In other words, the "local" keys will always match the "global" symbol keys.
-///
-/// The easiest way to achieve this would be to encode the whole dictionary every time.
-/// E.g. if the dict has symbols:
-///
-/// 0: "abc"
-/// 1: "defg"
-/// 2: "hi"
-/// 3: "jklmn"
-///
-/// And the column has key values:
-///
-/// 0, 2, 2  -- i.e, "abc", "hi", "hi"
-///
-/// We could encode the parquet dict buffer as so:
-/// [3, 0, 0, 0, 'a', 'b', 'c',
-///  4, 0, 0, 0, 'd', 'e', 'f', 'g',
-///  2, 0, 0, 0, 'h', 'i',
-///  5, 0, 0, 0, 'j', 'k', 'l', 'm', 'n']
-///
-/// But this would be unnecessarily wasteful.
-/// Instead, we employ two strategies to reduce the size of the dictionary:
-///   * The parquet dict is truncated to exclude symbols past the last used key.
-///   * Intermediate unused keys are encoded as an empty string.
-///
-/// For the example above, the encoded parquet dict buffer would be:
-///
-/// [3, 0, 0, 0, 'a', 'b', 'c',
-///  0, 0, 0, 0,
-///  2, 0, 0, 0, 'h', 'i']
+/// Encodes QuestDB symbol columns to Parquet pages.
 ///
-/// This strategy leads to two benefits:
-///   * During querying, the dict keys can be used directly as the column values - no lookups!
-///   * The resulting parquet file is still compatible with other readers.
-///
-/// The downsides are:
-///   * The dictionary is inflated with empty strings.
-///   * This is a reasonable tradeoff if most row groups end use a large subset of the global symbols.
-///   * This trades faster query performance for slightly higher memory usage during ingestion.
-///
-fn encode_dict_buffer(
-    local_keys: &[u32],
-    offsets: &[u64],
-    chars: &&[u8],
-    stats: &mut BinaryMaxMin,
-) -> ParquetResult<Vec<u8>> {
-    // Collect the set of unique values in the column.
-    // TODO(amunra): Reuse (cache allocation of) the `values_set` across multiple calls.
-    let mut end_value = None;
-    let values_set: HashSet<u32> = local_keys
-        .iter()
-        .cloned()
-        .inspect(|n| end_value = max(end_value, Some(*n + 1)))
-        .collect();
-    let end_value = end_value.unwrap_or(0);
-
-    // Compute an initial buffer capacity estimate for the dictionary buffer.
-    // We know that skipped values will use up exactly 4 bytes, and we expect
-    // other symbols to require 6 bytes per symbol in string length + 4 bytes len prefix.
-    let dense_count = values_set.len() as u32;
-    let sparse_count = end_value - dense_count;
-    let dict_buffer_size_estimate = (sparse_count * 4) + (dense_count * 10);
-
-    let mut dict_buffer = Vec::with_capacity(dict_buffer_size_estimate as usize);
-
-    // Walk each key up to `last_value` and encode it into the `dict_buffer`.
-    // Unused values are encoded as empty strings.
-    for key in 0..end_value {
-        // Always encode a zero-length. This is then overwritten with the actual length.
-        // This is to avoid double-buffering into a temporary `String`.
-        let key_index = dict_buffer.len();
-        dict_buffer.extend_from_slice(&(0u32).to_le_bytes());
-
-        if values_set.contains(&key) {
-            let qdb_global_offset = *offsets.get(key as usize).ok_or_else(|| {
-                fmt_layout_err!("could not find symbol with key {key} in global map")
-            })? as usize;
-            const UTF16_LEN_SIZE: usize = 4;
-            if (qdb_global_offset + UTF16_LEN_SIZE) > chars.len() {
-                return Err(fmt_layout_err!("global symbol map character data too small, begin offset {qdb_global_offset} out of bounds"));
+/// A fresh `symbol_to_pages` call used to allocate a new `HashSet<u32>`, `local_keys` vector,
+/// definition-level buffer and dictionary buffer on every invocation. For tables with many row
+/// groups this is a lot of allocation/deallocation churn for what are otherwise short-lived
+/// scratch buffers. `SymbolColumnEncoder` instead holds onto these buffers (and the ahash-backed
+/// uniqueness set, mirroring arrow-rs's `DictEncoder`) and `.clear()`s them between calls, so
+/// callers should keep one encoder per column around across row groups rather than constructing
+/// one per call.
+#[derive(Default)]
+pub struct SymbolColumnEncoder {
+    // Reused across calls: the set of distinct global symbol keys referenced by the column.
+    distinct_keys: AHashSet<u32>,
+    // Reused across calls: the column's local keys (indexes into the dict buffer).
+    local_keys: Vec<u32>,
+    // Reused across calls: `global key -> dense index` remap, only populated in `Compact` mode.
+    remap: HashMap<u32, u32>,
+    // Reused across calls: the parquet dict buffer under construction.
+    dict_buffer: Vec<u8>,
+    // Reused across calls: per-dict-key byte offset/length into `dict_buffer` (see `dict_value`).
+    key_offsets: Vec<u32>,
+    key_lens: Vec<u32>,
+    // Reused across pages and calls: each page's encoded def levels in `encode_symbol_data_pages`.
+    def_buffer: Vec<u8>,
+    // Set by the most recent `symbol_to_pages` call. `Compact` abandons the "dict index ==
+    // global symbol key" property that `KeyAligned` preserves, so callers relying on that
+    // invariant for zero-lookup reads need to be able to tell which one was used.
+    last_dict_encoding: Option<SymbolDictEncoding>,
+}
+
+impl SymbolColumnEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Which `SymbolDictEncoding` the most recent `symbol_to_pages` call chose, or `None` if
+    /// `symbol_to_pages` hasn't been called yet. Callers relying on dict index == global symbol
+    /// key (e.g. to skip a lookup) must check this is `KeyAligned` before doing so: `Compact`
+    /// remaps column values to dense indices and no longer has that property.
+    pub fn last_dict_encoding(&self) -> Option<SymbolDictEncoding> {
+        self.last_dict_encoding
+    }
+
+    pub fn symbol_to_pages(
+        &mut self,
+        column_values: &[i32],
+        offsets: &[u64],
+        chars: &[u8],
+        column_top: usize,
+        options: WriteOptions,
+        primitive_type: PrimitiveType,
+    ) -> ParquetResult<DynIter<'static, ParquetResult<Page>>> {
+        let num_rows = column_top + column_values.len();
+
+        let (max_key, dict_encoding) = self
+            .encode_symbols_dict(column_values, offsets, chars, options)
+            // TODO(amunra): Consolidate error handling,
+            //               Widen result type since it's currently too narrow to handle IO/logic errors.
+            .unwrap();
+        log::debug!("symbol column dict encoding: {dict_encoding:?}");
+        self.last_dict_encoding = Some(dict_encoding);
+        let bits_per_key = util::get_bit_width(max_key as u64);
+
+        let data_pages = self.encode_symbol_data_pages(
+            column_values,
+            column_top,
+            num_rows,
+            bits_per_key,
+            &primitive_type,
+            options,
+        )?;
+
+        let uniq_vals = if !self.dict_buffer.is_empty() {
+            max_key + 1
+        } else {
+            0
+        };
+        let dict_page = DictPage::new(self.dict_buffer.clone(), uniq_vals as usize, false);
+
+        let mut pages = Vec::with_capacity(data_pages.len() + 1);
+        pages.push(Ok(Page::Dict(dict_page)));
+        pages.extend(data_pages.into_iter().map(Ok));
+
+        Ok(DynIter::new(pages.into_iter()))
+    }
+
+    /// Populates `self.local_keys`, `self.dict_buffer`, `self.key_offsets` and `self.key_lens`
+    /// from the QuestDB symbols + usages, choosing between `encode_dict_buffer` (key-aligned) and
+    /// `encode_dict_buffer_compact` (dense) based on how sparse the global symbol table is
+    /// relative to this row group, or `options.force_compact_symbol_dict`.
+    ///
+    /// Returns the largest key value used (0 if no keys were used) and which dict layout was
+    /// chosen.
+    fn encode_symbols_dict(
+        &mut self,
+        column_vals: &[i32], // The QuestDB symbol column indices (i.e. numeric values).
+        offsets: &[u64],     // Memory-mapped offsets into the QuestDB global symbol table.
+        chars: &[u8], // Memory-mapped global symbol table. Sequence of 4-code-unit-len-prefixed utf16 strings.
+        options: WriteOptions,
+    ) -> ParquetResult<(u32, SymbolDictEncoding)> {
+        // All scratch state reused across calls is reset here, centrally, rather than leaving
+        // individual clears scattered across `encode_dict_buffer`/`encode_dict_buffer_compact` -
+        // that used to let `remap` leak a stale `Compact` row group's `global key -> dense index`
+        // entries into a later `KeyAligned` one, silently corrupting the bloom filter or panicking
+        // on an out-of-bounds index.
+        self.local_keys.clear();
+        self.distinct_keys.clear();
+        self.dict_buffer.clear();
+        self.remap.clear();
+        self.key_offsets.clear();
+        self.key_lens.clear();
+
+        let mut max_key = 0u32;
+        for &v in column_vals {
+            if v >= 0 {
+                let key = v as u32;
+                self.local_keys.push(key);
+                self.distinct_keys.insert(key);
+                max_key = max_key.max(key);
+            }
+        }
+        let end_value = if self.local_keys.is_empty() { 0 } else { max_key + 1 };
+        let dense_count = self.distinct_keys.len() as u32;
+        let sparse_count = end_value - dense_count;
+
+        let use_compact = options.force_compact_symbol_dict
+            || (end_value > 0
+                && (sparse_count as f64) > COMPACT_DICT_SPARSE_THRESHOLD * (end_value as f64));
+
+        if use_compact {
+            let dense_max_key = self.encode_dict_buffer_compact(offsets, chars)?;
+            Ok((dense_max_key, SymbolDictEncoding::Compact))
+        } else {
+            self.encode_dict_buffer(end_value, offsets, chars)?;
+            Ok((max_key, SymbolDictEncoding::KeyAligned))
+        }
+    }
+
+    /// Encode the parquet dict buffer from the QuestDB symbols + usages.
+    ///
+    /// The aim is to preserve the same numeric values in the column as the original QuestDB
+    /// column. In other words, the "local" keys will always match the "global" symbol keys.
+    ///
+    /// The easiest way to achieve this would be to encode the whole dictionary every time.
+    /// E.g. if the dict has symbols:
+    ///
+    /// 0: "abc"
+    /// 1: "defg"
+    /// 2: "hi"
+    /// 3: "jklmn"
+    ///
+    /// And the column has key values:
+    ///
+    /// 0, 2, 2  -- i.e, "abc", "hi", "hi"
+    ///
+    /// We could encode the parquet dict buffer as so:
+    /// [3, 0, 0, 0, 'a', 'b', 'c',
+    ///  4, 0, 0, 0, 'd', 'e', 'f', 'g',
+    ///  2, 0, 0, 0, 'h', 'i',
+    ///  5, 0, 0, 0, 'j', 'k', 'l', 'm', 'n']
+    ///
+    /// But this would be unnecessarily wasteful.
+    /// Instead, we employ two strategies to reduce the size of the dictionary:
+    ///   * The parquet dict is truncated to exclude symbols past the last used key.
+    ///   * Intermediate unused keys are encoded as an empty string.
+    ///
+    /// For the example above, the encoded parquet dict buffer would be:
+    ///
+    /// [3, 0, 0, 0, 'a', 'b', 'c',
+    ///  0, 0, 0, 0,
+    ///  2, 0, 0, 0, 'h', 'i']
+    ///
+    /// This strategy leads to two benefits:
+    ///   * During querying, the dict keys can be used directly as the column values - no lookups!
+    ///   * The resulting parquet file is still compatible with other readers.
+    ///
+    /// The downsides are:
+    ///   * The dictionary is inflated with empty strings.
+    ///   * This is a reasonable tradeoff if most row groups end use a large subset of the global symbols.
+    ///   * This trades faster query performance for slightly higher memory usage during ingestion.
+    ///
+    /// Populates `self.key_offsets`/`self.key_lens` with, for each key in `0..end_value`, the byte
+    /// offset and byte length of its utf8 data within `self.dict_buffer` (both 0 for unused keys).
+    /// Callers use these to slice out a given key's utf8 bytes without re-parsing the 4-byte
+    /// length prefixes.
+    fn encode_dict_buffer(&mut self, end_value: u32, offsets: &[u64], chars: &[u8]) -> ParquetResult<()> {
+        // Compute an initial buffer capacity estimate for the dictionary buffer.
+        // We know that skipped values will use up exactly 4 bytes, and we expect
+        // other symbols to require 6 bytes per symbol in string length + 4 bytes len prefix.
+        let dense_count = self.distinct_keys.len() as u32;
+        let sparse_count = end_value - dense_count;
+        let dict_buffer_size_estimate = (sparse_count * 4) + (dense_count * 10);
+        self.dict_buffer.reserve(dict_buffer_size_estimate as usize);
+
+        // `key_offsets`/`key_lens` were already cleared in `encode_symbols_dict`; just size them.
+        self.key_offsets.resize(end_value as usize, 0);
+        self.key_lens.resize(end_value as usize, 0);
+
+        // Walk each key up to `end_value` and encode it into `self.dict_buffer`.
+        // Unused values are encoded as empty strings.
+        for key in 0..end_value {
+            if self.distinct_keys.contains(&key) {
+                let (offset, len) = encode_dict_entry(&mut self.dict_buffer, offsets, chars, key)?;
+                self.key_offsets[key as usize] = offset;
+                self.key_lens[key as usize] = len;
+            } else {
+                // Always encode a zero-length for unused keys to keep dictionary index N aligned
+                // with local key N.
+                self.dict_buffer.extend_from_slice(&(0u32).to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// An alternative to `encode_dict_buffer` for when the global symbol table is sparse relative
+    /// to what this row group touches: rather than padding out every unused key in `0..end_value`
+    /// as an empty string, build a dictionary containing only the distinct keys actually
+    /// referenced (from `self.distinct_keys`), sorted by (and remapped to) contiguous dense
+    /// indices `0..dense_count`.
+    ///
+    /// This sacrifices the "dictionary index == column value, no lookups" property that
+    /// `encode_dict_buffer` provides, in exchange for a dictionary (and data page) that no longer
+    /// grows with the size of the global symbol table. Remaps `self.local_keys` in place through
+    /// `self.remap` to the new dense indices, and populates `self.key_offsets`/`self.key_lens`
+    /// (mirroring `encode_dict_buffer`'s layout, now indexed by dense index). Returns the largest
+    /// dense index used, or 0 if none.
+    fn encode_dict_buffer_compact(&mut self, offsets: &[u64], chars: &[u8]) -> ParquetResult<u32> {
+        let mut distinct_keys: Vec<u32> = self.distinct_keys.iter().cloned().collect();
+        distinct_keys.sort_unstable();
+        let dense_count = distinct_keys.len();
+
+        // `remap`/`key_offsets`/`key_lens` were already cleared in `encode_symbols_dict`; just size
+        // `key_offsets`/`key_lens` here.
+        self.key_offsets.resize(dense_count, 0);
+        self.key_lens.resize(dense_count, 0);
+
+        for (dense_key, &key) in distinct_keys.iter().enumerate() {
+            self.remap.insert(key, dense_key as u32);
+            let (offset, len) = encode_dict_entry(&mut self.dict_buffer, offsets, chars, key)?;
+            self.key_offsets[dense_key] = offset;
+            self.key_lens[dense_key] = len;
+        }
+
+        for key in self.local_keys.iter_mut() {
+            *key = self.remap[key];
+        }
+
+        Ok(dense_count.saturating_sub(1) as u32)
+    }
+
+    /// Returns the utf8 bytes of dictionary `key`, given the offsets/lengths produced by
+    /// `encode_dict_buffer`/`encode_dict_buffer_compact`. Unused keys map to an empty slice.
+    fn dict_value(&self, key: u32) -> &[u8] {
+        let offset = self.key_offsets[key as usize] as usize;
+        let len = self.key_lens[key as usize] as usize;
+        &self.dict_buffer[offset..(offset + len)]
+    }
+
+    /// Like `dict_value`, but takes a QuestDB *global* symbol key rather than a dictionary index
+    /// (the two only coincide in `KeyAligned` mode; in `Compact` mode the key must first be
+    /// translated through `self.remap`).
+    fn dict_value_for_global_key(&self, key: u32) -> &[u8] {
+        let dict_index = self.remap.get(&key).copied().unwrap_or(key);
+        self.dict_value(dict_index)
+    }
+
+    /// Builds a Split-Block Bloom Filter (SBBF) over the distinct symbol strings referenced by the
+    /// most recent `symbol_to_pages` call, for readers to use for row-group-skipping equality
+    /// pushdown. Returns the filter's serialized bytes (ready to be written as-is as the column
+    /// chunk's bloom filter block) and the number of distinct values it was built from.
+    ///
+    /// This isn't wired into `symbol_to_pages`'s page output: callers that want a bloom filter
+    /// call this separately (before the next `symbol_to_pages` call clears the scratch state) and
+    /// patch `bloom_filter_offset`/`bloom_filter_length` into the column chunk metadata once the
+    /// block has been written out.
+    pub fn build_bloom_filter(&self) -> (Vec<u8>, usize) {
+        let distinct_count = self.distinct_keys.len();
+        let num_blocks = bloom_filter_num_blocks(distinct_count);
+        let mut blocks = vec![[0u32; 8]; num_blocks];
+
+        for &key in &self.distinct_keys {
+            let value = self.dict_value_for_global_key(key);
+            let hash = xxh64(value, 0);
+            let block_index = (((hash >> 32) * num_blocks as u64) >> 32) as usize;
+            bloom_filter_set_block_bits(&mut blocks[block_index], hash as u32);
+        }
+
+        let mut bytes = Vec::with_capacity(blocks.len() * 32);
+        for block in &blocks {
+            for word in block {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        (bytes, distinct_count)
+    }
+
+    /// Splits the symbol column's data into one or more [`Page::Data`] pages, each bounded by
+    /// `options.data_page_size` (when set).
+    ///
+    /// Before growing a page by one more row, we estimate the resulting data-page size as
+    /// `1 (bits_per_key byte) + RleEncoder::max_buffer_size(bits_per_key, keys_in_page) +
+    /// RleEncoder::max_buffer_size(1, rows_in_page)`, without actually encoding the candidate
+    /// range - `self.def_buffer` is only populated once the range is settled, via a single
+    /// `encode_bool_iter` call per page. Once the estimate would exceed the limit, the page is
+    /// flushed with the last accepted range and a new page starts from the rejected row.
+    ///
+    /// Each page gets its own min/max `BinaryMaxMin` statistics, computed only from the symbols it
+    /// references, alongside a `SizeStatistics` (when `options.write_statistics` is set): the
+    /// total unencoded utf8 byte size of the symbols referenced by the page, and the
+    /// definition/repetition level histograms (`[row_count]` for repetition, since symbol columns
+    /// are non-nested). The definition-level histogram's null bucket is *not* the same `null_count`
+    /// passed to `build_plain_page`/page `Statistics` below: that one only counts actual negative
+    /// keys in `column_values` (matching this column's pre-paged stats accounting), whereas the
+    /// histogram must reflect every row actually encoded with a `false` def level, which also
+    /// includes `column_top` placeholder rows.
+    fn encode_symbol_data_pages(
+        &mut self,
+        column_values: &[i32],
+        column_top: usize,
+        num_rows: usize,
+        bits_per_key: u8,
+        primitive_type: &PrimitiveType,
+        options: WriteOptions,
+    ) -> ParquetResult<Vec<Page>> {
+        let page_size_limit = options.data_page_size.unwrap_or(usize::MAX);
+
+        let is_value_at = |row: usize| row >= column_top && column_values[row - column_top] > -1;
+
+        let mut pages = Vec::new();
+        let mut row_start = 0usize;
+        let mut key_start = 0usize;
+
+        loop {
+            let mut row_end = row_start;
+            let mut key_end = key_start;
+            let mut null_count = 0usize;
+
+            while row_end < num_rows {
+                let candidate_row_end = row_end + 1;
+                let candidate_key_end = key_end + if is_value_at(row_end) { 1 } else { 0 };
+
+                // Estimate the def-level buffer size analytically rather than actually encoding
+                // the growing prefix on every row: `encode_bool_iter` is O(rows in page), so
+                // calling it here on every iteration would make page construction O(rows^2).
+                let rows_in_page = candidate_row_end - row_start;
+                let keys_in_page = (candidate_key_end - key_start) as u32;
+                let def_levels_estimate = RleEncoder::max_buffer_size(1, rows_in_page);
+                let estimate = 1
+                    + RleEncoder::max_buffer_size(bits_per_key as u32, keys_in_page as usize)
+                    + def_levels_estimate;
+
+                if row_end > row_start && estimate > page_size_limit {
+                    break;
+                }
+
+                // `column_top` rows get a `false` (null) definition level like any other missing
+                // value, but - matching this column's pre-paged behavior - are not counted in
+                // `null_count`: only actual negative keys within `column_values` are. The def
+                // levels themselves, `key_end` and the `definition_level_histogram` (derived from
+                // them below) do account for `column_top`, via `is_value_at`.
+                if row_end >= column_top && column_values[row_end - column_top] <= -1 {
+                    null_count += 1;
+                }
+                row_end = candidate_row_end;
+                key_end = candidate_key_end;
+            }
+
+            // Encode the def levels once, for the exact accepted range, now that the page's row
+            // boundaries are settled.
+            self.def_buffer.clear();
+            encode_bool_iter(
+                &mut self.def_buffer,
+                (row_start..row_end).map(is_value_at),
+                options.version,
+            )?;
+
+            let page_keys = &self.local_keys[key_start..key_end];
+            let mut stats = BinaryMaxMin::new(primitive_type);
+            let mut unencoded_byte_array_data_bytes = 0i64;
+            for &key in page_keys {
+                let value = self.dict_value(key);
+                stats.update(value);
+                unencoded_byte_array_data_bytes += value.len() as i64;
             }
-            let qdb_utf16_len_buf = &chars[qdb_global_offset..];
-            let (qdb_utf16_len, qdb_utf16_buf) = qdb_utf16_len_buf.split_at(UTF16_LEN_SIZE);
-
-            let qdb_utf16_len =
-                i32::from_le_bytes(qdb_utf16_len.try_into().expect("4 bytes sliced")) as usize;
-
-            // In the `.c` (chars) file, the length is stored as a little-endian 32-bit integer of
-            // code unit counts. We multiply by 2 to get the byte length of the UTF-16 string.
-            if qdb_utf16_buf.len() < (qdb_utf16_len * 2) {
-                return Err(fmt_layout_err!(
-                    "global symbol map character data too small, end offset {} out of bounds",
-                    qdb_global_offset + qdb_utf16_len * 2
-                ));
+
+            let mut data_buffer = self.def_buffer.clone();
+            let definition_levels_byte_length = data_buffer.len();
+            data_buffer.push(bits_per_key);
+            let keys_iter = ExactSizedIter::new(page_keys.iter().cloned(), page_keys.len());
+            encode_u32(&mut data_buffer, keys_iter, bits_per_key as u32)?;
+
+            let row_count = row_end - row_start;
+            // Distinct from `null_count` above (which, matching this column's pre-paged stats,
+            // excludes `column_top` rows): the histogram must reflect the def levels actually
+            // encoded into `self.def_buffer`, where a `column_top` row gets a 0 (null) level just
+            // like a negative key does. `page_keys.len()` is exactly the number of def-level-1
+            // (non-null) rows in this page, since a row only contributed to `key_end` when
+            // `is_value_at` was true, so the def-level-0 count is whatever's left over.
+            let def0_count = row_count - page_keys.len();
+            let size_statistics = options.write_statistics.then(|| SizeStatistics {
+                unencoded_byte_array_data_bytes: Some(unencoded_byte_array_data_bytes),
+                // Symbol columns are non-nested, so there's a single repetition level (0) and a
+                // single optional definition level (0 for null, 1 for non-null).
+                repetition_level_histogram: Some(vec![row_count as i64]),
+                definition_level_histogram: Some(vec![def0_count as i64, page_keys.len() as i64]),
+            });
+
+            let data_page = build_plain_page(
+                data_buffer,
+                row_count,
+                null_count,
+                definition_levels_byte_length,
+                if options.write_statistics {
+                    Some(stats.into_parquet_stats(null_count))
+                } else {
+                    None
+                },
+                size_statistics,
+                primitive_type.clone(),
+                options,
+                Encoding::RleDictionary,
+            )?;
+            pages.push(Page::Data(data_page));
+
+            row_start = row_end;
+            key_start = key_end;
+
+            if row_start >= num_rows {
+                break;
             }
-            let qdb_utf16_buf: &[u16] = unsafe { std::mem::transmute(qdb_utf16_buf) };
-            let qdb_utf16_buf = &qdb_utf16_buf[..qdb_utf16_len];
-            let utf8_len = write_utf8_from_utf16(&mut dict_buffer, qdb_utf16_buf)
-                .map_err(|e| ParquetError::Utf16Decode { source: e })?;
-            let utf8_buf = &dict_buffer[(key_index + 4)..(key_index + 4 + utf8_len)];
-
-            // Update the page's min/max statistics for the referenced UTF-8 strings.
-            stats.update(utf8_buf);
-
-            // Go back and overwrite the zero-length with the actual length.
-            let utf8_len_bytes = (utf8_len as u32).to_le_bytes();
-            dict_buffer[key_index..(key_index + 4)].copy_from_slice(&utf8_len_bytes);
         }
+
+        Ok(pages)
+    }
+}
+
+/// Writes dictionary entry `key`'s utf8 string (looked up from the QuestDB global symbol table)
+/// into `dict_buffer`, preceded by its 4-byte length prefix. Returns the byte offset (past the
+/// length prefix) and byte length of the written utf8 data.
+fn encode_dict_entry(
+    dict_buffer: &mut Vec<u8>,
+    offsets: &[u64],
+    chars: &[u8],
+    key: u32,
+) -> ParquetResult<(u32, u32)> {
+    let key_index = dict_buffer.len();
+    // Always encode a zero-length. This is then overwritten with the actual length.
+    // This is to avoid double-buffering into a temporary `String`.
+    dict_buffer.extend_from_slice(&(0u32).to_le_bytes());
+
+    let qdb_global_offset = *offsets
+        .get(key as usize)
+        .ok_or_else(|| fmt_layout_err!("could not find symbol with key {key} in global map"))?
+        as usize;
+    const UTF16_LEN_SIZE: usize = 4;
+    if (qdb_global_offset + UTF16_LEN_SIZE) > chars.len() {
+        return Err(fmt_layout_err!("global symbol map character data too small, begin offset {qdb_global_offset} out of bounds"));
     }
-    Ok(dict_buffer)
+    let qdb_utf16_len_buf = &chars[qdb_global_offset..];
+    let (qdb_utf16_len, qdb_utf16_buf) = qdb_utf16_len_buf.split_at(UTF16_LEN_SIZE);
+
+    let qdb_utf16_len =
+        i32::from_le_bytes(qdb_utf16_len.try_into().expect("4 bytes sliced")) as usize;
+
+    // In the `.c` (chars) file, the length is stored as a little-endian 32-bit integer of
+    // code unit counts. We multiply by 2 to get the byte length of the UTF-16 string.
+    if qdb_utf16_buf.len() < (qdb_utf16_len * 2) {
+        return Err(fmt_layout_err!(
+            "global symbol map character data too small, end offset {} out of bounds",
+            qdb_global_offset + qdb_utf16_len * 2
+        ));
+    }
+    let qdb_utf16_buf: &[u16] = unsafe { std::mem::transmute(qdb_utf16_buf) };
+    let qdb_utf16_buf = &qdb_utf16_buf[..qdb_utf16_len];
+    let utf8_len = write_utf8_from_utf16(dict_buffer, qdb_utf16_buf)
+        .map_err(|e| ParquetError::Utf16Decode { source: e })?;
+    let utf8_start = key_index + 4;
+
+    // Go back and overwrite the zero-length with the actual length.
+    let utf8_len_bytes = (utf8_len as u32).to_le_bytes();
+    dict_buffer[key_index..(key_index + 4)].copy_from_slice(&utf8_len_bytes);
+
+    Ok((utf8_start as u32, utf8_len as u32))
 }
 
 fn write_utf8_from_utf16(dest: &mut Vec<u8>, src: &[u16]) -> Result<usize, DecodeUtf16Error> {
@@ -167,6 +556,15 @@ fn write_utf8_from_utf16(dest: &mut Vec<u8>, src: &[u16]) -> Result<usize, Decod
     Ok(dest.len() - start_count)
 }
 
+/// Encode QuestDB symbols to Parquet using a one-shot `SymbolColumnEncoder`.
+///
+/// Prefer constructing a `SymbolColumnEncoder` once and calling
+/// `SymbolColumnEncoder::symbol_to_pages` for each row group when encoding many row groups for the
+/// same column, so that its scratch buffers are reused instead of reallocated every time.
+///
+/// Also returns the `SymbolDictEncoding` chosen for this row group: `Compact` abandons the
+/// "dict index == global symbol key" property that readers may rely on for zero-lookup reads, so
+/// callers need to know which layout they got.
 pub fn symbol_to_pages(
     column_values: &[i32],
     offsets: &[u64],
@@ -174,68 +572,185 @@ pub fn symbol_to_pages(
     column_top: usize,
     options: WriteOptions,
     primitive_type: PrimitiveType,
-) -> ParquetResult<DynIter<'static, ParquetResult<Page>>> {
-    let num_rows = column_top + column_values.len();
-    let mut null_count = 0;
+) -> ParquetResult<(DynIter<'static, ParquetResult<Page>>, SymbolDictEncoding)> {
+    let mut encoder = SymbolColumnEncoder::new();
+    let pages = encoder.symbol_to_pages(
+        column_values,
+        offsets,
+        chars,
+        column_top,
+        options,
+        primitive_type,
+    )?;
+    let dict_encoding = encoder
+        .last_dict_encoding()
+        .expect("symbol_to_pages always sets last_dict_encoding before returning");
+    Ok((pages, dict_encoding))
+}
 
-    let deflevels_iter = (0..num_rows).map(|i| {
-        if i < column_top {
-            false
-        } else {
-            let key = column_values[i - column_top];
-            // negative denotes a null value
-            if key > -1 {
-                true
-            } else {
-                null_count += 1;
-                false
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a QuestDB global symbol table (`offsets`/`chars`) for `symbols`, in the same
+    /// len-prefixed-utf16 layout `encode_dict_entry` reads from.
+    fn build_symbol_table(symbols: &[&str]) -> (Vec<u64>, Vec<u8>) {
+        let mut offsets = Vec::with_capacity(symbols.len());
+        let mut chars = Vec::new();
+        for s in symbols {
+            offsets.push(chars.len() as u64);
+            let utf16: Vec<u16> = s.encode_utf16().collect();
+            chars.extend_from_slice(&(utf16.len() as i32).to_le_bytes());
+            for unit in utf16 {
+                chars.extend_from_slice(&unit.to_le_bytes());
             }
         }
-    });
-    let mut data_buffer = vec![];
-    encode_bool_iter(&mut data_buffer, deflevels_iter, options.version)?;
-    let definition_levels_byte_length = data_buffer.len();
-
-    let mut stats = BinaryMaxMin::new(&primitive_type);
-    let (dict_buffer, keys, max_key) =
-        encode_symbols_dict(column_values, offsets, chars, &mut stats)
-            // TODO(amunra): Consolidate error handling,
-            //               Widen result type since it's currently too narrow to handle IO/logic errors.
+        (offsets, chars)
+    }
+
+    #[test]
+    fn bloom_filter_set_block_bits_hash_zero_sets_bit_zero_everywhere() {
+        // Every salt multiplied by a hash of 0 is 0, and `0 >> 27 == 0`, so each of the block's
+        // eight words should end up with only its lowest bit set.
+        let mut block = [0u32; 8];
+        bloom_filter_set_block_bits(&mut block, 0);
+        assert_eq!(block, [1u32; 8]);
+    }
+
+    #[test]
+    fn bloom_filter_set_block_bits_is_cumulative_across_calls() {
+        let mut block = [0u32; 8];
+        bloom_filter_set_block_bits(&mut block, 0);
+        bloom_filter_set_block_bits(&mut block, u32::MAX);
+        // A second call with a different hash must only ever add bits, never clear the ones
+        // `hash = 0` already set.
+        for word in block {
+            assert_eq!(word & 1, 1);
+        }
+    }
+
+    #[test]
+    fn bloom_filter_num_blocks_rounds_up_to_256_bit_blocks() {
+        assert_eq!(bloom_filter_num_blocks(0), 1); // 1 (min) * 10 bits/value, floored to 256 bits -> 1 block
+        assert_eq!(bloom_filter_num_blocks(100), 4); // 100 * 10 = 1000 bits -> ceil(1000 / 256) = 4 blocks
+    }
+
+    #[test]
+    fn build_bloom_filter_sets_exactly_the_bits_bloom_filter_set_block_bits_would() {
+        let (offsets, chars) = build_symbol_table(&["abc", "defg", "hi"]);
+        let mut encoder = SymbolColumnEncoder::new();
+        let options = WriteOptions::default();
+        encoder
+            .encode_symbols_dict(&[0, 2, 1, 2], &offsets, &chars, options)
             .unwrap();
-    let bits_per_key = util::get_bit_width(max_key as u64);
-
-    let non_null_len = column_values.len() - null_count;
-    let keys = ExactSizedIter::new(keys.into_iter(), non_null_len);
-    // bits_per_key as a single byte...
-    data_buffer.push(bits_per_key);
-    // followed by the encoded keys.
-    encode_u32(&mut data_buffer, keys, bits_per_key as u32)?;
-
-    let data_page = build_plain_page(
-        data_buffer,
-        num_rows,
-        null_count,
-        definition_levels_byte_length,
-        if options.write_statistics {
-            Some(stats.into_parquet_stats(null_count))
-        } else {
-            None
-        },
-        primitive_type,
-        options,
-        Encoding::RleDictionary,
-    )?;
 
-    let uniq_vals = if !dict_buffer.is_empty() {
-        max_key + 1
-    } else {
-        0
-    };
-    let dict_page = DictPage::new(dict_buffer, uniq_vals as usize, false);
-
-    Ok(DynIter::new(
-        [Page::Dict(dict_page), Page::Data(data_page)]
-            .into_iter()
-            .map(Ok),
-    ))
+        let (bytes, distinct_count) = encoder.build_bloom_filter();
+        assert_eq!(distinct_count, 3);
+        let num_blocks = bloom_filter_num_blocks(distinct_count);
+        assert_eq!(bytes.len(), num_blocks * 32);
+
+        // Several keys can land in the same block (with 3 values there is only one block), so
+        // the expected filter accumulates every key's bits before comparing.
+        let mut expected_blocks = vec![[0u32; 8]; num_blocks];
+        for key in 0..3u32 {
+            let value = encoder.dict_value_for_global_key(key).to_vec();
+            let hash = xxh64(&value, 0);
+            let block_index = (((hash >> 32) * num_blocks as u64) >> 32) as usize;
+            bloom_filter_set_block_bits(&mut expected_blocks[block_index], hash as u32);
+        }
+
+        for (block_index, expected_block) in expected_blocks.iter().enumerate() {
+            let block_bytes = &bytes[block_index * 32..(block_index + 1) * 32];
+            for (word_index, expected_word) in expected_block.iter().enumerate() {
+                let actual_word = u32::from_le_bytes(
+                    block_bytes[word_index * 4..word_index * 4 + 4]
+                        .try_into()
+                        .unwrap(),
+                );
+                assert_eq!(
+                    actual_word, *expected_word,
+                    "block {block_index} word {word_index}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compact_mode_remaps_local_keys_to_dense_indices() {
+        let (offsets, chars) = build_symbol_table(&["a", "b", "c", "d", "e"]);
+        // Only keys 0 and 4 out of the 5-entry global table are referenced. The 3 unused keys
+        // (60%) stay under `COMPACT_DICT_SPARSE_THRESHOLD`, so `Compact` mode is forced here; it
+        // should remap both keys to a 2-entry dense range.
+        let column_vals = [0, 4, 4, 0];
+        let mut encoder = SymbolColumnEncoder::new();
+        let options = WriteOptions {
+            force_compact_symbol_dict: true,
+            ..WriteOptions::default()
+        };
+        let (max_key, dict_encoding) = encoder
+            .encode_symbols_dict(&column_vals, &offsets, &chars, options)
+            .unwrap();
+
+        assert_eq!(dict_encoding, SymbolDictEncoding::Compact);
+        assert_eq!(max_key, 1); // two distinct keys -> dense indices 0, 1
+
+        // `local_keys` now holds dense indices, not the original global keys.
+        assert_eq!(encoder.local_keys, vec![0, 1, 1, 0]);
+        assert_eq!(encoder.dict_value(0), b"a");
+        assert_eq!(encoder.dict_value(1), b"e");
+
+        // The global-key lookup path must still resolve back to the same strings through `remap`.
+        assert_eq!(encoder.dict_value_for_global_key(0), b"a");
+        assert_eq!(encoder.dict_value_for_global_key(4), b"e");
+    }
+
+    #[test]
+    fn encode_symbol_data_pages_never_drops_or_duplicates_rows() {
+        use parquet2::schema::types::PhysicalType;
+
+        let (offsets, chars) = build_symbol_table(&["a", "bb", "ccc"]);
+        let column_values: Vec<i32> = (0..20).map(|i| i % 3).collect();
+        let mut encoder = SymbolColumnEncoder::new();
+        let options = WriteOptions {
+            // Pathologically small limit: forces the `estimate > page_size_limit` break on
+            // almost every row, which is exactly what would trip an off-by-one that drops or
+            // duplicates a row at a page boundary, or that could produce an empty page.
+            data_page_size: Some(1),
+            write_statistics: true,
+            ..WriteOptions::default()
+        };
+        let (max_key, _) = encoder
+            .encode_symbols_dict(&column_values, &offsets, &chars, options)
+            .unwrap();
+        let bits_per_key = util::get_bit_width(max_key as u64);
+        let primitive_type = PrimitiveType::from_physical("value".to_string(), PhysicalType::ByteArray);
+
+        let pages = encoder
+            .encode_symbol_data_pages(
+                &column_values,
+                0,
+                column_values.len(),
+                bits_per_key,
+                &primitive_type,
+                options,
+            )
+            .unwrap();
+
+        assert!(
+            pages.len() > 1,
+            "data_page_size=1 should force more than one page"
+        );
+        let mut total_rows = 0usize;
+        for page in &pages {
+            let Page::Data(data_page) = page else {
+                panic!("encode_symbol_data_pages should only produce Page::Data entries")
+            };
+            // The `row_end > row_start` guard in the growth loop must always let at least one
+            // row through before breaking, even if that single row's estimate already exceeds
+            // `page_size_limit` - otherwise a page could come out empty.
+            assert!(data_page.num_values() > 0, "every page must contain at least one row");
+            total_rows += data_page.num_values();
+        }
+        assert_eq!(total_rows, column_values.len());
+    }
 }